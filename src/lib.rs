@@ -9,18 +9,117 @@ pub mod experiment {
         external_sort::{Sortable, ExternalSorter}
     };
     use csv::ReaderBuilder;
+    use lmdb::{Environment, Transaction, WriteFlags};
     use pyo3::prelude::*;
+    use pyo3::types::PyBytes;
     use pyo3::wrap_pyfunction;
     use std::{fmt::Debug, io::{Read, Write}};
-    use itertools::iproduct;
+    use rayon::prelude::*;
+    use rayon::ThreadPoolBuilder;
     use serde_derive::{Deserialize, Serialize};
     use std::cmp::Ordering;
+    use std::collections::BinaryHeap;
+    use std::path::Path;
 
     type VecOfResults = Vec<CorResult>;
     type TupleExpressionValues = (String, Vec<f64>);
     pub type Batch = Vec<TupleExpressionValues>;
     type LazyMatrix = Box<dyn Iterator<Item = TupleExpressionValues>>;
 
+    /// Default LMDB map size (10 GiB) used when callers don't override it.
+    const DEFAULT_LMDB_MAP_SIZE: usize = 10 * 1024 * 1024 * 1024;
+
+    /// Number of rows of the larger (lazy) matrix `all_vs_all` processes at
+    /// a time. Bounds the memory a single batch's Cartesian product against
+    /// the collected matrix can use, regardless of how large the lazy side
+    /// is.
+    const LAZY_MATRIX_CHUNK_ROWS: usize = 1_000;
+
+    /// Where `all_vs_all` should park the results it produces.
+    ///
+    /// `InMemory` keeps today's behaviour of returning a `Vec<CorResult>`.
+    /// `Lmdb` streams each surviving result into a memory-mapped LMDB
+    /// database instead, so result sets far larger than RAM can be handled.
+    #[derive(Clone, Debug)]
+    pub enum OutputSink {
+        InMemory,
+        Lmdb { path: String, map_size: usize },
+    }
+
+    /// What `Computation::all_vs_all`/`compute` hand back, mirroring the
+    /// `OutputSink` the caller asked for.
+    pub enum ComputationResult {
+        InMemory(VecOfResults),
+        Lmdb { path: String, row_count: u64 },
+    }
+
+    /// Lazily decodes `CorResult` rows back out of an LMDB database written
+    /// by `all_vs_all`'s `OutputSink::Lmdb` path, in rank order. Exposed to
+    /// Python as an iterator so results far larger than RAM can be paged
+    /// through instead of materialized all at once.
+    #[pyclass]
+    pub struct LmdbResultIter {
+        env: Environment,
+        db: lmdb::Database,
+        next_key: u64,
+        row_count: u64,
+    }
+
+    impl LmdbResultIter {
+        pub fn open(path: &str, row_count: u64, map_size: usize) -> Self {
+            // Mirrors `write_to_lmdb`'s directory handling so opening for
+            // reads doesn't panic just because `path` wasn't created yet.
+            std::fs::create_dir_all(path).unwrap();
+
+            let env = Environment::new()
+                .set_map_size(map_size)
+                .open(Path::new(path))
+                .unwrap();
+            let db = env.open_db(None).unwrap();
+
+            LmdbResultIter {
+                env,
+                db,
+                next_key: 0,
+                row_count,
+            }
+        }
+    }
+
+    #[pymethods]
+    impl LmdbResultIter {
+        #[new]
+        #[pyo3(signature = (path, row_count, map_size = DEFAULT_LMDB_MAP_SIZE))]
+        fn py_new(path: String, row_count: u64, map_size: usize) -> Self {
+            Self::open(&path, row_count, map_size)
+        }
+
+        fn __iter__(slf: PyRef<Self>) -> PyRef<Self> {
+            slf
+        }
+
+        fn __next__(mut slf: PyRefMut<Self>) -> Option<CorResult> {
+            slf.next()
+        }
+    }
+
+    impl Iterator for LmdbResultIter {
+        type Item = CorResult;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.next_key >= self.row_count {
+                return None;
+            }
+
+            let txn = self.env.begin_ro_txn().unwrap();
+            let key = self.next_key.to_be_bytes();
+            let raw = txn.get(self.db, &key).ok()?;
+            let decoded = CorResult::decode(&mut std::io::Cursor::new(raw));
+            self.next_key += 1;
+            decoded
+        }
+    }
+
     #[pyclass]
     #[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
     pub struct CorResult {
@@ -36,6 +135,40 @@ pub mod experiment {
         adjusted_p_value: Option<f64>,
     }
 
+    /// Column-oriented view of a `VecOfResults`, used by `correlate_to_pickle`
+    /// so pandas can build a `DataFrame` straight from the pickled dict
+    /// without a per-row conversion pass.
+    #[derive(Serialize)]
+    struct ColumnarResults {
+        gene: Vec<String>,
+        gem: Vec<String>,
+        correlation: Vec<f64>,
+        p_value: Vec<f64>,
+        adjusted_p_value: Vec<Option<f64>>,
+    }
+
+    impl From<&VecOfResults> for ColumnarResults {
+        fn from(results: &VecOfResults) -> Self {
+            let mut columnar = ColumnarResults {
+                gene: Vec::with_capacity(results.len()),
+                gem: Vec::with_capacity(results.len()),
+                correlation: Vec::with_capacity(results.len()),
+                p_value: Vec::with_capacity(results.len()),
+                adjusted_p_value: Vec::with_capacity(results.len()),
+            };
+
+            for result in results {
+                columnar.gene.push(result.gene.clone());
+                columnar.gem.push(result.gem.clone());
+                columnar.correlation.push(result.correlation);
+                columnar.p_value.push(result.p_value);
+                columnar.adjusted_p_value.push(result.adjusted_p_value);
+            }
+
+            columnar
+        }
+    }
+
     impl std::fmt::Display for CorResult {
         // This trait requires `fmt` with this exact signature.
         fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -89,65 +222,212 @@ pub mod experiment {
             correlation_threhold: f64,
             sort_buf_size: u64,
             adjustment_method: AdjustmentMethod,
-        ) -> VecOfResults {
+            keep_top_k: Option<usize>,
+            output_sink: OutputSink,
+            threads: usize,
+        ) -> ComputationResult {
             let total_number_of_elements: u64 = len_m1 * len_m3;
 
-            // We need a collected object for right-side of the iproduct macro. In this
+            // We need a collected object for right-side of the Cartesian product. In this
             // case it gets the smaller one to collect
             let (lazy_m, collected_m) = if len_m1 > len_m3 { (m1, m3) } else { (m3, m1) };
 
+            // Only the smaller side is collected. The larger (lazy) side is
+            // pulled in bounded batches below instead, so at no point do we
+            // need to hold more than `LAZY_MATRIX_CHUNK_ROWS` of its rows —
+            // let alone the full `len_m1 * len_m3` product — in memory.
             let collected_m = collected_m.collect::<Vec<TupleExpressionValues>>();
+            let mut lazy_m = lazy_m;
 
             let correlation_struct = get_correlation_method(correlation_method, number_of_columns);
-            let correlations_and_p_values =
-                iproduct!(lazy_m, collected_m).map(|(tuple1, tuple3)| {
-                    // Gene and GEM
-                    let gene = tuple1.0;
-                    let gem = tuple3.0;
-
-                    let (correlation, p_value) =
-                        correlation_struct.correlate(tuple1.1.as_slice(), tuple3.1.as_slice());
-
-                    CorResult {
-                        gene,
-                        gem,
-                        correlation,
-                        p_value,
-                        adjusted_p_value: None,
+
+            // threads == 0 means "let rayon pick a default"
+            let pool = ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+
+            pool.install(|| {
+                // Pulls `LAZY_MATRIX_CHUNK_ROWS` rows of `lazy_m` at a time and
+                // runs that batch's full product against `collected_m` in
+                // parallel, yielding the resulting `CorResult`s one by one.
+                // Driving this lazily (instead of collecting the whole
+                // product up front) keeps memory bounded to one batch's
+                // worth of results regardless of `len_m1 * len_m3`.
+                let correlations_and_p_values = std::iter::from_fn(move || {
+                    let batch: Batch = (&mut lazy_m).take(LAZY_MATRIX_CHUNK_ROWS).collect();
+                    if batch.is_empty() {
+                        None
+                    } else {
+                        Some(batch)
                     }
+                })
+                .flat_map(|batch| {
+                    batch
+                        .into_par_iter()
+                        .flat_map(|tuple1| {
+                            collected_m.par_iter().map(move |tuple3| {
+                                let (correlation, p_value) = correlation_struct
+                                    .correlate(tuple1.1.as_slice(), tuple3.1.as_slice());
+
+                                CorResult {
+                                    gene: tuple1.0.clone(),
+                                    gem: tuple3.0.clone(),
+                                    correlation,
+                                    p_value,
+                                    adjusted_p_value: None,
+                                }
+                            })
+                        })
+                        .collect::<VecOfResults>()
+                        .into_iter()
                 });
 
-            // Sorting
-            let sorted: Box<dyn Iterator<Item = CorResult>> = match adjustment_method {
-                AdjustmentMethod::Bonferroni => Box::new(correlations_and_p_values),
-                _ => {
-                    // Benjamini-Hochberg and Benajmini-Yekutieli needs sort by p-value to
-                    // make the adjustment
-                    let mut sorter = ExternalSorter::new(sort_buf_size as usize);
-                    Box::new(sorter.sort(correlations_and_p_values).unwrap())
-                }
-            };
+                if let Some(k) = keep_top_k {
+                    // Bounded alternative to the full sort/collect pipeline below: keep
+                    // only the `k` smallest-p-value results (after the correlation
+                    // threshold filter) in a max-heap that evicts its largest p-value
+                    // entry once it grows past `k`, so the full O(len_m1 * len_m3)
+                    // `CorResult` vector and the external sorter are never needed.
+                    //
+                    // BH/BY still need each survivor's rank within the *entire* tested
+                    // population (not just among the k survivors) to adjust correctly,
+                    // so every p-value is also collected into a plain `Vec<f64>` —
+                    // much lighter than a `Vec<CorResult>` — and sorted once to answer
+                    // rank lookups by binary search.
+                    let (mut p_values, heap) = correlations_and_p_values.fold(
+                        (Vec::new(), BinaryHeap::new()),
+                        |(mut p_values, mut heap): (Vec<f64>, BinaryHeap<CorResult>), cor_result| {
+                            p_values.push(cor_result.p_value);
+
+                            if cor_result.correlation.abs() >= correlation_threhold {
+                                heap.push(cor_result);
+
+                                if heap.len() > k {
+                                    heap.pop();
+                                }
+                            }
+
+                            (p_values, heap)
+                        },
+                    );
+
+                    p_values.par_sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+
+                    let mut top_k = heap.into_iter().collect::<VecOfResults>();
+                    top_k.par_sort_unstable_by(|a, b| a.p_value.partial_cmp(&b.p_value).unwrap());
+
+                    let mut adjustment_struct =
+                        get_adjustment_method(adjustment_method, total_number_of_elements as f64);
+                    let adjusted = top_k.into_iter().map(|mut cor_result| {
+                        // Number of population p-values strictly smaller than this one.
+                        // For distinct p-values this is the rank `sorted.enumerate()`
+                        // assigns below; tied p-values all get the lowest rank of their
+                        // group here, whereas `enumerate()` numbers them consecutively.
+                        let rank = p_values.partition_point(|&p| p < cor_result.p_value);
+                        let q_value = adjustment_struct.adjust(cor_result.p_value, rank);
+                        cor_result.adjusted_p_value = Some(q_value);
+                        cor_result
+                    });
 
-            // Ranking
-            let ranked = sorted.enumerate();
+                    match output_sink {
+                        OutputSink::InMemory => ComputationResult::InMemory(adjusted.collect()),
+                        OutputSink::Lmdb { path, map_size } => {
+                            let row_count = Self::write_to_lmdb(&path, map_size, adjusted);
+                            ComputationResult::Lmdb { path, row_count }
+                        }
+                    }
+                } else {
+                    // Sorting
+                    let sorted: Box<dyn Iterator<Item = CorResult>> = match adjustment_method {
+                        AdjustmentMethod::Bonferroni => Box::new(correlations_and_p_values),
+                        _ => {
+                            // Benjamini-Hochberg and Benajmini-Yekutieli needs sort by p-value to
+                            // make the adjustment. When the result set fits in `sort_buf_size` we
+                            // collect and sort it in place with rayon; otherwise the external
+                            // sorter consumes the batch-by-batch stream above directly, so it's
+                            // still the only thing ever holding the full result set (and it does
+                            // so on disk, not in RAM).
+                            if total_number_of_elements <= sort_buf_size {
+                                let mut collected: VecOfResults = correlations_and_p_values.collect();
+                                collected.par_sort_unstable_by(|a, b| {
+                                    a.p_value.partial_cmp(&b.p_value).unwrap()
+                                });
+                                Box::new(collected.into_iter())
+                            } else {
+                                let mut sorter = ExternalSorter::new(sort_buf_size as usize);
+                                Box::new(sorter.sort(correlations_and_p_values).unwrap())
+                            }
+                        }
+                    };
+
+                    // Ranking
+                    let ranked = sorted.enumerate();
+
+                    // Filtering
+                    let filtered = ranked.filter(|(_, cor_and_p_value)| {
+                        cor_and_p_value.correlation.abs() >= correlation_threhold
+                    });
 
-            // Filtering
-            let filtered = ranked.filter(|(_, cor_and_p_value)| {
-                cor_and_p_value.correlation.abs() >= correlation_threhold
-            });
+                    // Adjustment
+                    let mut adjustment_struct =
+                        get_adjustment_method(adjustment_method, total_number_of_elements as f64);
+                    let adjusted = filtered.map(|(rank, mut cor_and_p_value)| {
+                        let p_value = cor_and_p_value.p_value;
+                        let q_value = adjustment_struct.adjust(p_value, rank);
 
-            // Adjustment
-            let mut adjustment_struct =
-                get_adjustment_method(adjustment_method, total_number_of_elements as f64);
-            let adjusted = filtered.map(|(rank, mut cor_and_p_value)| {
-                let p_value = cor_and_p_value.p_value;
-                let q_value = adjustment_struct.adjust(p_value, rank);
+                        cor_and_p_value.adjusted_p_value = Some(q_value);
+                        cor_and_p_value
+                    });
 
-                cor_and_p_value.adjusted_p_value = Some(q_value);
-                cor_and_p_value
-            });
+                    // Streamed straight into the chosen sink as it's produced — for
+                    // `OutputSink::Lmdb` this avoids materializing the full result set
+                    // in memory before writing it back out again, except for the BH/BY
+                    // case above that already fit (and was deliberately collected)
+                    // within `sort_buf_size`.
+                    match output_sink {
+                        OutputSink::InMemory => ComputationResult::InMemory(adjusted.collect()),
+                        OutputSink::Lmdb { path, map_size } => {
+                            let row_count = Self::write_to_lmdb(&path, map_size, adjusted);
+                            ComputationResult::Lmdb { path, row_count }
+                        }
+                    }
+                }
+            })
+        }
 
-            adjusted.collect::<VecOfResults>()
+        /// Writes `items` (already in final rank order) into a fresh LMDB
+        /// database at `path`, keyed by a monotonically increasing
+        /// big-endian `u64` so iteration order matches rank order. Returns
+        /// the number of rows written.
+        fn write_to_lmdb(
+            path: &str,
+            map_size: usize,
+            items: impl Iterator<Item = CorResult>,
+        ) -> u64 {
+            // LMDB opens an existing directory, it doesn't create one —
+            // `lmdb_path` is normally a fresh output location, so make sure
+            // it's there first.
+            std::fs::create_dir_all(path).unwrap();
+
+            let env = Environment::new()
+                .set_map_size(map_size)
+                .open(Path::new(path))
+                .unwrap();
+            let db = env.open_db(None).unwrap();
+
+            let mut txn = env.begin_rw_txn().unwrap();
+            let mut row_count: u64 = 0;
+            for item in items {
+                let mut encoded = Vec::new();
+                item.encode(&mut encoded);
+                txn.put(db, &row_count.to_be_bytes(), &encoded, WriteFlags::empty())
+                    .unwrap();
+                row_count += 1;
+            }
+            txn.commit().unwrap();
+
+            row_count
         }
 
         fn get_df(&self, path: &str) -> LazyMatrix {
@@ -205,7 +485,10 @@ pub mod experiment {
             correlation_threhold: f64,
             sort_buf_size: u64,
             adjustment_method: AdjustmentMethod,
-        ) -> VecOfResults;
+            keep_top_k: Option<usize>,
+            output_sink: OutputSink,
+            threads: usize,
+        ) -> ComputationResult;
     }
 
     pub struct ExperimentFromFiles {
@@ -220,7 +503,10 @@ pub mod experiment {
             correlation_threhold: f64,
             sort_buf_size: u64,
             adjustment_method: AdjustmentMethod,
-        ) -> VecOfResults {
+            keep_top_k: Option<usize>,
+            output_sink: OutputSink,
+            threads: usize,
+        ) -> ComputationResult {
             let (m1, len_m1, m3, len_m3, number_of_columns) =
                 self.get_both_df_and_shape(self.file_1_path.as_str(), self.file_2_path.as_str());
 
@@ -234,6 +520,9 @@ pub mod experiment {
                 correlation_threhold,
                 sort_buf_size,
                 adjustment_method,
+                keep_top_k,
+                output_sink,
+                threads,
             )
         }
     }
@@ -249,11 +538,25 @@ pub mod experiment {
     #[pymodule]
     fn ggca(_py: Python, m: &PyModule) -> PyResult<()> {
         m.add_function(wrap_pyfunction!(correlate, m)?)?;
+        m.add_function(wrap_pyfunction!(correlate_to_pickle, m)?)?;
+        m.add_class::<LmdbResultIter>()?;
 
         Ok(())
     }
 
     #[pyfunction]
+    #[pyo3(signature = (
+        file_1_path,
+        file_2_path,
+        correlation_method,
+        correlation_threhold,
+        sort_buf_size,
+        adjustment_method,
+        keep_top_k = None,
+        lmdb_path = None,
+        lmdb_map_size = DEFAULT_LMDB_MAP_SIZE,
+        threads = 0,
+    ))]
     pub fn correlate(
         py: Python,
         file_1_path: String,
@@ -262,8 +565,89 @@ pub mod experiment {
         correlation_threhold: f64,
         sort_buf_size: u64,
         adjustment_method: i32,
-    ) -> PyResult<VecOfResults> {
-        py.allow_threads(|| {
+        keep_top_k: Option<usize>,
+        lmdb_path: Option<String>,
+        lmdb_map_size: usize,
+        threads: usize,
+    ) -> PyResult<PyObject> {
+        let result = py.allow_threads(|| {
+            let experiment = new_from_files(file_1_path, file_2_path);
+            let correlation_method = match correlation_method {
+                1 => CorrelationMethod::Spearman,
+                2 => CorrelationMethod::Kendall,
+                _ => CorrelationMethod::Pearson,
+            };
+
+            let adjustment_method = match adjustment_method {
+                1 => AdjustmentMethod::BenjaminiHochberg,
+                2 => AdjustmentMethod::BenjaminiYekutieli,
+                _ => AdjustmentMethod::Bonferroni,
+            };
+
+            let output_sink = match lmdb_path {
+                Some(path) => OutputSink::Lmdb {
+                    path,
+                    map_size: lmdb_map_size,
+                },
+                None => OutputSink::InMemory,
+            };
+
+            experiment.compute(
+                correlation_method,
+                correlation_threhold,
+                sort_buf_size,
+                adjustment_method,
+                keep_top_k,
+                output_sink,
+                threads,
+            )
+        });
+
+        // `InMemory` keeps returning a plain list of `CorResult`s; `Lmdb`
+        // hands back `(path, row_count, map_size)` so Python can construct
+        // an `LmdbResultIter` and page through the database lazily instead
+        // of materializing everything up front.
+        match result {
+            ComputationResult::InMemory(results) => Ok(results.into_py(py)),
+            ComputationResult::Lmdb { path, row_count } => {
+                Ok((path, row_count, lmdb_map_size).into_py(py))
+            }
+        }
+    }
+
+    /// Same computation as `correlate`, but serializes the whole result
+    /// vector into Python's native pickle format in one shot instead of
+    /// letting pyo3 convert every `CorResult` into a Python object field by
+    /// field. `columnar` selects a `{gene: [...], gem: [...], ...}` layout
+    /// (pandas-friendly) over the default list of row dicts. Either the
+    /// pickled bytes are returned, or written to `output_path` if given.
+    #[pyfunction]
+    #[pyo3(signature = (
+        file_1_path,
+        file_2_path,
+        correlation_method,
+        correlation_threhold,
+        sort_buf_size,
+        adjustment_method,
+        keep_top_k = None,
+        threads = 0,
+        columnar = false,
+        output_path = None,
+    ))]
+    pub fn correlate_to_pickle(
+        py: Python,
+        file_1_path: String,
+        file_2_path: String,
+        correlation_method: i32,
+        correlation_threhold: f64,
+        sort_buf_size: u64,
+        adjustment_method: i32,
+        keep_top_k: Option<usize>,
+        threads: usize,
+        columnar: bool,
+        output_path: Option<String>,
+    ) -> PyResult<PyObject> {
+        let pickled = py.allow_threads(|| {
             let experiment = new_from_files(file_1_path, file_2_path);
             let correlation_method = match correlation_method {
                 1 => CorrelationMethod::Spearman,
@@ -282,8 +666,185 @@ pub mod experiment {
                 correlation_threhold,
                 sort_buf_size,
                 adjustment_method,
+                keep_top_k,
+                OutputSink::InMemory,
+                threads,
+            );
+
+            let results = match result {
+                ComputationResult::InMemory(results) => results,
+                ComputationResult::Lmdb { .. } => unreachable!("correlate_to_pickle always uses OutputSink::InMemory"),
+            };
+
+            if columnar {
+                serde_pickle::to_vec(&ColumnarResults::from(&results), Default::default()).unwrap()
+            } else {
+                serde_pickle::to_vec(&results, Default::default()).unwrap()
+            }
+        });
+
+        match output_path {
+            Some(path) => {
+                std::fs::write(path, pickled)?;
+                Ok(py.None())
+            }
+            None => Ok(PyBytes::new(py, &pickled).into()),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn cor_result(p_value: f64) -> CorResult {
+            CorResult {
+                gene: "g".to_string(),
+                gem: "m".to_string(),
+                correlation: 1.0,
+                p_value,
+                adjusted_p_value: None,
+            }
+        }
+
+        #[test]
+        fn top_k_heap_keeps_smallest_p_values() {
+            let mut heap: BinaryHeap<CorResult> = BinaryHeap::new();
+            let k = 3;
+
+            for p_value in [0.5, 0.1, 0.9, 0.2, 0.4, 0.05] {
+                heap.push(cor_result(p_value));
+                if heap.len() > k {
+                    heap.pop();
+                }
+            }
+
+            let mut kept: Vec<f64> = heap.into_iter().map(|r| r.p_value).collect();
+            kept.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            assert_eq!(kept, vec![0.05, 0.1, 0.2]);
+        }
+
+        #[test]
+        fn rank_lookup_matches_full_population_position() {
+            // Same values `top_k_heap_keeps_smallest_p_values` retains 0.1 for, but
+            // here the rank must come from this *entire* population, not just the
+            // 3 survivors, or BH/BY adjustment would be computed against the wrong N.
+            let mut p_values = vec![0.5, 0.1, 0.9, 0.2, 0.4, 0.05];
+            p_values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            assert_eq!(p_values.partition_point(|&p| p < 0.05), 0);
+            assert_eq!(p_values.partition_point(|&p| p < 0.1), 1);
+            assert_eq!(p_values.partition_point(|&p| p < 0.2), 2);
+        }
+
+        struct TestComputation;
+
+        impl Computation for TestComputation {
+            fn compute(
+                &self,
+                _correlation_method: CorrelationMethod,
+                _correlation_threhold: f64,
+                _sort_buf_size: u64,
+                _adjustment_method: AdjustmentMethod,
+                _keep_top_k: Option<usize>,
+                _output_sink: OutputSink,
+                _threads: usize,
+            ) -> ComputationResult {
+                unimplemented!("tests only exercise all_vs_all directly")
+            }
+        }
+
+        fn small_matrices() -> (Vec<TupleExpressionValues>, Vec<TupleExpressionValues>) {
+            let m1 = vec![
+                ("g1".to_string(), vec![1.0, 2.0, 3.0, 4.0]),
+                ("g2".to_string(), vec![4.0, 3.0, 2.0, 1.0]),
+                ("g3".to_string(), vec![1.0, 3.0, 2.0, 4.0]),
+            ];
+            let m3 = vec![
+                ("m1".to_string(), vec![2.0, 4.0, 6.0, 8.0]),
+                ("m2".to_string(), vec![8.0, 6.0, 4.0, 2.0]),
+                ("m3".to_string(), vec![1.0, 1.0, 2.0, 2.0]),
+            ];
+
+            (m1, m3)
+        }
+
+        #[test]
+        fn keep_top_k_matches_full_population_adjustment() {
+            // This is the regression 0f1d370 fixed: `keep_top_k`'s survivors must
+            // be ranked against the whole tested population (like the non-top-k
+            // path ranks everything), not just the k-sized retained subset.
+            let computation = TestComputation;
+            let (m1_rows, m3_rows) = small_matrices();
+            let number_of_columns = 4;
+
+            let to_lazy_matrices = || -> (LazyMatrix, LazyMatrix) {
+                (
+                    Box::new(m1_rows.clone().into_iter()),
+                    Box::new(m3_rows.clone().into_iter()),
+                )
+            };
+
+            let (m1, m3) = to_lazy_matrices();
+            let full = computation.all_vs_all(
+                m1,
+                m1_rows.len() as u64,
+                m3,
+                m3_rows.len() as u64,
+                number_of_columns,
+                CorrelationMethod::Pearson,
+                0.0,
+                u64::MAX,
+                AdjustmentMethod::BenjaminiHochberg,
+                None,
+                OutputSink::InMemory,
+                1,
+            );
+
+            let (m1, m3) = to_lazy_matrices();
+            let top_k = computation.all_vs_all(
+                m1,
+                m1_rows.len() as u64,
+                m3,
+                m3_rows.len() as u64,
+                number_of_columns,
+                CorrelationMethod::Pearson,
+                0.0,
+                u64::MAX,
+                AdjustmentMethod::BenjaminiHochberg,
+                Some(2),
+                OutputSink::InMemory,
+                1,
             );
-            Ok(result)
-        })
+
+            let full_results = match full {
+                ComputationResult::InMemory(results) => results,
+                ComputationResult::Lmdb { .. } => panic!("expected in-memory results"),
+            };
+            let top_k_results = match top_k {
+                ComputationResult::InMemory(results) => results,
+                ComputationResult::Lmdb { .. } => panic!("expected in-memory results"),
+            };
+
+            assert_eq!(top_k_results.len(), 2);
+
+            let mut full_by_key: std::collections::HashMap<(String, String), f64> = full_results
+                .into_iter()
+                .map(|r| ((r.gene, r.gem), r.adjusted_p_value.unwrap()))
+                .collect();
+
+            for result in &top_k_results {
+                let key = (result.gene.clone(), result.gem.clone());
+                let expected = full_by_key
+                    .remove(&key)
+                    .expect("top-k survivor should also appear in the full population run");
+
+                assert!(
+                    (result.adjusted_p_value.unwrap() - expected).abs() < 1e-9,
+                    "adjusted p-value for {:?} should match the full-population run",
+                    key
+                );
+            }
+        }
     }
-}
\ No newline at end of file
+}